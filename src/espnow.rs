@@ -0,0 +1,126 @@
+use crate::wifi::WifiCommand;
+use crate::wifi::WifiManager;
+use esp_idf_sys::esp_now_add_peer;
+use esp_idf_sys::esp_now_init;
+use esp_idf_sys::esp_now_peer_info_t;
+use esp_idf_sys::esp_now_recv_info_t;
+use esp_idf_sys::esp_now_register_recv_cb;
+use esp_idf_sys::esp_now_register_send_cb;
+use esp_idf_sys::esp_now_send;
+use esp_idf_sys::esp_now_send_status_t;
+use esp_idf_sys::wifi_interface_t_WIFI_IF_STA;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::SendError;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+
+pub type MacAddress = [u8; 6];
+
+pub enum EspNowCommand {
+    RegisterPeer(MacAddress),
+    Send(MacAddress, Vec<u8>),
+}
+
+static RECEIVE_SENDER: Mutex<Option<Sender<(MacAddress, Vec<u8>)>>> = Mutex::new(None);
+
+pub struct EspNowManager {
+    #[allow(unused)]
+    driver_thread: JoinHandle<()>,
+    command_sender: Sender<EspNowCommand>,
+    pub incoming: Receiver<(MacAddress, Vec<u8>)>,
+}
+
+impl EspNowManager {
+    fn add_peer(mac: MacAddress, channel: u8) {
+        let mut peer_info: esp_now_peer_info_t = unsafe { std::mem::zeroed() };
+        peer_info.peer_addr = mac;
+        peer_info.channel = channel;
+        peer_info.ifidx = wifi_interface_t_WIFI_IF_STA;
+        unsafe {
+            if esp_idf_sys::esp!(esp_now_add_peer(&peer_info)).is_err() {
+                println!("Failed to register ESP-NOW peer");
+            }
+        }
+    }
+
+    fn send_frame(mac: MacAddress, data: &[u8]) {
+        unsafe {
+            if esp_idf_sys::esp!(esp_now_send(mac.as_ptr(), data.as_ptr(), data.len())).is_err() {
+                println!("Failed to send ESP-NOW frame");
+            }
+        }
+    }
+
+    unsafe extern "C" fn on_recv(info: *const esp_now_recv_info_t, data: *const u8, len: i32) {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(std::slice::from_raw_parts((*info).src_addr, 6));
+        let payload = std::slice::from_raw_parts(data, len as usize).to_vec();
+        if let Ok(sender) = RECEIVE_SENDER.lock() {
+            if let Some(sender) = sender.as_ref() {
+                let _ = sender.send((mac, payload));
+            }
+        }
+    }
+
+    unsafe extern "C" fn on_send(_mac: *const u8, _status: esp_now_send_status_t) {}
+
+    /// Asks `wifi_manager` to fix its radio to `channel`, then brings up ESP-NOW on its
+    /// own thread and registers every peer on that same channel, since ESP-NOW peers are
+    /// only reachable on whatever channel the Wi-Fi driver currently occupies. The channel
+    /// change is queued on the Wi-Fi thread and is not waited for, and a later
+    /// `set_fixed_channel` call on `wifi_manager` does not change the channel used here.
+    pub fn init(
+        wifi_manager: &mut WifiManager,
+        channel: u8,
+    ) -> Result<EspNowManager, SendError<WifiCommand>> {
+        wifi_manager.set_fixed_channel(Some(channel))?;
+
+        let (command_sender, command_receiver) = mpsc::channel::<EspNowCommand>();
+        let (receive_sender, incoming) = mpsc::channel::<(MacAddress, Vec<u8>)>();
+        *RECEIVE_SENDER.lock().unwrap() = Some(receive_sender);
+
+        let thread_builder = thread::Builder::new().stack_size(8192);
+
+        Ok(EspNowManager {
+            command_sender,
+            incoming,
+            driver_thread: thread_builder
+                .spawn(move || {
+                    unsafe {
+                        if esp_idf_sys::esp!(esp_now_init()).is_err() {
+                            panic!("Couldn't initialize ESP-NOW");
+                        }
+                        esp_idf_sys::esp!(esp_now_register_recv_cb(Some(EspNowManager::on_recv)))
+                            .unwrap();
+                        esp_idf_sys::esp!(esp_now_register_send_cb(Some(EspNowManager::on_send)))
+                            .unwrap();
+                    }
+
+                    loop {
+                        match command_receiver.recv() {
+                            Ok(EspNowCommand::RegisterPeer(mac)) => {
+                                EspNowManager::add_peer(mac, channel);
+                            }
+                            Ok(EspNowCommand::Send(mac, data)) => {
+                                EspNowManager::send_frame(mac, &data);
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                })
+                .unwrap(),
+        })
+    }
+
+    pub fn register_peer(&mut self, mac: MacAddress) -> Result<(), SendError<EspNowCommand>> {
+        self.command_sender.send(EspNowCommand::RegisterPeer(mac))
+    }
+
+    pub fn send(&mut self, mac: MacAddress, data: &[u8]) -> Result<(), SendError<EspNowCommand>> {
+        self.command_sender
+            .send(EspNowCommand::Send(mac, data.to_vec()))
+    }
+}
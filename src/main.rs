@@ -1,3 +1,4 @@
+mod espnow;
 mod wifi;
 
 use esp_idf_sys::{self as _}; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
@@ -8,13 +9,20 @@ fn main() {
 
     println!("Bricc booted, starting wifi");
 
-    #[allow(unused)]
     let mut wifi_manager = wifi::WifiManager::init();
     wifi_manager
         .set_ap_wpa2_psk("bricc".into(), "showscreen".into())
         .unwrap();
     loop {
-        println!("Sitting around doing nothing.");
+        match wifi_manager.current_status() {
+            wifi::WifiStatus::Connected(ssid, signal_strength, ip_info) => println!(
+                "Connected to {} ({}dBm), IP {}",
+                ssid, signal_strength, ip_info.address
+            ),
+            wifi::WifiStatus::ApOnly(ssid) => println!("Broadcasting AP {}", ssid),
+            wifi::WifiStatus::Disconnected => println!("Disconnected"),
+            wifi::WifiStatus::Error(_) => println!("Wifi error"),
+        }
         std::thread::sleep(Duration::from_secs(10));
     }
 }
@@ -10,6 +10,10 @@ use esp_idf_svc::sysloop::EspSysLoopStack;
 use esp_idf_svc::wifi::*;
 use esp_idf_sys::EspError;
 use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::net::UdpSocket;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::mpsc::SendError;
 use std::sync::Arc;
@@ -18,6 +22,12 @@ use std::thread::JoinHandle;
 use std::time::Duration;
 
 const WIFI_SCAN_PERIOD: Duration = Duration::from_secs(60);
+const WIFI_LIVENESS_CHECK_PERIOD: Duration = Duration::from_secs(2);
+const WIFI_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const NVS_NETWORKS_KEY: &str = "networks";
+const NVS_NETWORKS_MAX_LEN: usize = 4096;
+const CAPTIVE_PORTAL_GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+const DNS_PORT: &str = "0.0.0.0:53";
 
 pub type SSID = String;
 pub type PSKKey = String;
@@ -25,21 +35,64 @@ pub type WifiSignalStrength = u8;
 
 pub enum WifiCommand {
     ConnectWPA2PSK(SSID, PSKKey),
+    ConnectWPA2PSKStatic(SSID, PSKKey, Ipv4Settings),
+    ConnectWPA2Enterprise {
+        ssid: SSID,
+        identity: String,
+        username: String,
+        password: String,
+        ca_cert: Option<Vec<u8>>,
+    },
     CreateApWPA2PSK(SSID, PSKKey),
+    ForgetNetwork(SSID),
+    SetApFallback(bool),
+    SetFixedChannel(Option<u8>),
+}
+
+#[derive(Clone, Copy)]
+pub struct Ipv4Settings {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+}
+
+#[derive(Clone)]
+struct EnterpriseCredentials {
+    identity: String,
+    username: String,
+    password: String,
+    ca_cert: Option<Vec<u8>>,
 }
 
-enum WifiStatus {
-    Connected(SSID, WifiSignalStrength),
+#[derive(Clone)]
+pub enum WifiStatus {
+    Connected(SSID, WifiSignalStrength, IpInfo),
     ApOnly(SSID),
     Disconnected,
     Error(WifiError),
 }
 
-enum WifiError {
+#[derive(Clone)]
+pub enum WifiError {
     Fatal(String),
     NetworkNotFound(SSID),
 }
 
+#[derive(Clone, Copy)]
+pub struct IpInfo {
+    pub address: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+}
+
+#[derive(PartialEq)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+    Backoff,
+}
+
 impl From<EspError> for WifiError {
     fn from(_: EspError) -> WifiError {
         WifiError::Fatal("Unknown error during wifi operation".into())
@@ -48,7 +101,52 @@ impl From<EspError> for WifiError {
 
 struct WifiManagerConfig {
     client_configs: HashMap<SSID, ClientConfiguration>,
+    enterprise_configs: HashMap<SSID, EnterpriseCredentials>,
+    static_ip_configs: HashMap<SSID, Ipv4Settings>,
     ap_config: Option<AccessPointConfiguration>,
+    ap_fallback: bool,
+    fixed_channel: Option<u8>,
+}
+
+struct CaptivePortalDns {
+    shutdown: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl CaptivePortalDns {
+    fn spawn() -> CaptivePortalDns {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = Arc::clone(&shutdown);
+        let thread = thread::Builder::new()
+            .stack_size(4096)
+            .spawn(move || {
+                let socket = match UdpSocket::bind(DNS_PORT) {
+                    Ok(socket) => socket,
+                    Err(_) => {
+                        println!("Captive portal DNS responder failed to bind port 53");
+                        return;
+                    }
+                };
+                let _ = socket.set_read_timeout(Some(Duration::from_millis(500)));
+                let mut buf = [0u8; 512];
+                while !thread_shutdown.load(Ordering::Relaxed) {
+                    let (len, src) = match socket.recv_from(&mut buf) {
+                        Ok(result) => result,
+                        Err(_) => continue,
+                    };
+                    if let Some(response) = WifiManager::build_dns_redirect(&buf[..len]) {
+                        let _ = socket.send_to(&response, src);
+                    }
+                }
+            })
+            .unwrap();
+        CaptivePortalDns { shutdown, thread }
+    }
+
+    fn stop(self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
 }
 
 pub struct WifiManager {
@@ -56,6 +154,7 @@ pub struct WifiManager {
     connection_thread: JoinHandle<()>,
     command_sender: Sender<WifiCommand>,
     status_receiver: Receiver<WifiStatus>,
+    last_status: WifiStatus,
 }
 
 impl WifiManager {
@@ -66,7 +165,7 @@ impl WifiManager {
     ) -> WifiManagerConfig {
         configs.ap_config = Some(AccessPointConfiguration {
             ssid,
-            channel: 1,
+            channel: configs.fixed_channel.unwrap_or(1),
             password: key,
             auth_method: AuthMethod::WPA2WPA3Personal,
             ..Default::default()
@@ -81,12 +180,442 @@ impl WifiManager {
         let config = ClientConfiguration {
             ssid: ssid.clone().into(),
             password: key.into(),
-            channel: None,
+            channel: configs.fixed_channel,
             ..Default::default()
         };
         configs.client_configs.insert(ssid, config);
         configs
     }
+    fn connect_wpa2_psk_static(
+        mut configs: WifiManagerConfig,
+        ssid: SSID,
+        key: PSKKey,
+        static_ip: Ipv4Settings,
+    ) -> WifiManagerConfig {
+        let config = ClientConfiguration {
+            ssid: ssid.clone().into(),
+            password: key.into(),
+            channel: configs.fixed_channel,
+            ..Default::default()
+        };
+        configs.client_configs.insert(ssid.clone(), config);
+        configs.static_ip_configs.insert(ssid, static_ip);
+        configs
+    }
+    fn connect_wpa2_enterprise(
+        mut configs: WifiManagerConfig,
+        ssid: SSID,
+        identity: String,
+        username: String,
+        password: String,
+        ca_cert: Option<Vec<u8>>,
+    ) -> WifiManagerConfig {
+        let config = ClientConfiguration {
+            ssid: ssid.clone().into(),
+            auth_method: AuthMethod::WPA2Enterprise,
+            channel: configs.fixed_channel,
+            ..Default::default()
+        };
+        configs.client_configs.insert(ssid.clone(), config);
+        configs.enterprise_configs.insert(
+            ssid,
+            EnterpriseCredentials {
+                identity,
+                username,
+                password,
+                ca_cert,
+            },
+        );
+        configs
+    }
+
+    fn set_ap_fallback(mut configs: WifiManagerConfig, ap_fallback: bool) -> WifiManagerConfig {
+        configs.ap_fallback = ap_fallback;
+        configs
+    }
+
+    fn set_fixed_channel(
+        mut configs: WifiManagerConfig,
+        fixed_channel: Option<u8>,
+    ) -> WifiManagerConfig {
+        configs.fixed_channel = fixed_channel;
+        for client_config in configs.client_configs.values_mut() {
+            client_config.channel = fixed_channel;
+        }
+        if let Some(ap_config) = &mut configs.ap_config {
+            ap_config.channel = fixed_channel.unwrap_or(1);
+        }
+        configs
+    }
+
+    fn build_dns_redirect(query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < 12 {
+            return None;
+        }
+        let qdcount = u16::from_be_bytes([query[4], query[5]]);
+        if qdcount != 1 {
+            return None;
+        }
+
+        let mut pos = 12;
+        while pos < query.len() && query[pos] != 0 {
+            pos += 1 + query[pos] as usize;
+        }
+        let question_end = pos + 1 + 4; // null label + QTYPE + QCLASS
+        if question_end > query.len() {
+            return None;
+        }
+        let question = &query[12..question_end];
+
+        let mut response = Vec::with_capacity(question_end + 16);
+        response.extend_from_slice(&query[0..2]); // transaction id
+        response.extend_from_slice(&[0x81, 0x80]); // standard response, no error
+        response.extend_from_slice(&[0x00, 0x01]); // QDCOUNT
+        response.extend_from_slice(&[0x00, 0x01]); // ANCOUNT
+        response.extend_from_slice(&[0x00, 0x00]); // NSCOUNT
+        response.extend_from_slice(&[0x00, 0x00]); // ARCOUNT
+        response.extend_from_slice(question);
+        response.extend_from_slice(&[0xc0, 0x0c]); // name pointer to the question
+        response.extend_from_slice(&[0x00, 0x01]); // TYPE A
+        response.extend_from_slice(&[0x00, 0x01]); // CLASS IN
+        response.extend_from_slice(&[0x00, 0x00, 0x00, 0x3c]); // TTL 60s
+        response.extend_from_slice(&[0x00, 0x04]); // RDLENGTH
+        response.extend_from_slice(&CAPTIVE_PORTAL_GATEWAY.octets());
+        Some(response)
+    }
+
+    fn forget_network(mut configs: WifiManagerConfig, ssid: &SSID) -> WifiManagerConfig {
+        configs.client_configs.remove(ssid);
+        configs.enterprise_configs.remove(ssid);
+        configs.static_ip_configs.remove(ssid);
+        configs
+    }
+
+    fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+        buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        buf.extend_from_slice(bytes);
+    }
+
+    fn decode_bytes<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+        if pos.checked_add(4)? > bytes.len() {
+            return None;
+        }
+        let len = u32::from_le_bytes(bytes[*pos..*pos + 4].try_into().ok()?) as usize;
+        *pos += 4;
+        let end = pos.checked_add(len)?;
+        if end > bytes.len() {
+            return None;
+        }
+        let slice = &bytes[*pos..end];
+        *pos = end;
+        Some(slice)
+    }
+
+    fn decode_u8(bytes: &[u8], pos: &mut usize) -> Option<u8> {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        Some(byte)
+    }
+
+    fn decode_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+        if pos.checked_add(4)? > bytes.len() {
+            return None;
+        }
+        let value = u32::from_le_bytes(bytes[*pos..*pos + 4].try_into().ok()?);
+        *pos += 4;
+        Some(value)
+    }
+
+    fn encode_ipv4(buf: &mut Vec<u8>, addr: Ipv4Addr) {
+        buf.extend_from_slice(&addr.octets());
+    }
+
+    fn decode_ipv4(bytes: &[u8], pos: &mut usize) -> Option<Ipv4Addr> {
+        if pos.checked_add(4)? > bytes.len() {
+            return None;
+        }
+        let octets: [u8; 4] = bytes[*pos..*pos + 4].try_into().ok()?;
+        *pos += 4;
+        Some(Ipv4Addr::from(octets))
+    }
+
+    fn serialize_configs(configs: &WifiManagerConfig) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(configs.client_configs.len() as u32).to_le_bytes());
+        for (ssid, client_config) in configs.client_configs.iter() {
+            WifiManager::encode_bytes(&mut buf, ssid.as_bytes());
+            match configs.enterprise_configs.get(ssid) {
+                None => {
+                    buf.push(0);
+                    WifiManager::encode_bytes(&mut buf, client_config.password.as_bytes());
+                }
+                Some(creds) => {
+                    buf.push(1);
+                    WifiManager::encode_bytes(&mut buf, creds.identity.as_bytes());
+                    WifiManager::encode_bytes(&mut buf, creds.username.as_bytes());
+                    WifiManager::encode_bytes(&mut buf, creds.password.as_bytes());
+                    match &creds.ca_cert {
+                        None => buf.push(0),
+                        Some(ca_cert) => {
+                            buf.push(1);
+                            WifiManager::encode_bytes(&mut buf, ca_cert);
+                        }
+                    }
+                }
+            }
+            match configs.static_ip_configs.get(ssid) {
+                None => buf.push(0),
+                Some(static_ip) => {
+                    buf.push(1);
+                    WifiManager::encode_ipv4(&mut buf, static_ip.address);
+                    WifiManager::encode_ipv4(&mut buf, static_ip.netmask);
+                    WifiManager::encode_ipv4(&mut buf, static_ip.gateway);
+                    WifiManager::encode_ipv4(&mut buf, static_ip.dns);
+                }
+            }
+        }
+        buf
+    }
+
+    fn deserialize_configs(
+        bytes: &[u8],
+    ) -> Option<(
+        HashMap<SSID, ClientConfiguration>,
+        HashMap<SSID, EnterpriseCredentials>,
+        HashMap<SSID, Ipv4Settings>,
+    )> {
+        let mut client_configs = HashMap::new();
+        let mut enterprise_configs = HashMap::new();
+        let mut static_ip_configs = HashMap::new();
+        let mut pos = 0;
+        let count = WifiManager::decode_u32(bytes, &mut pos)?;
+        for _ in 0..count {
+            let ssid =
+                String::from_utf8_lossy(WifiManager::decode_bytes(bytes, &mut pos)?).into_owned();
+            let is_enterprise = WifiManager::decode_u8(bytes, &mut pos)?;
+            if is_enterprise == 0 {
+                let password =
+                    String::from_utf8_lossy(WifiManager::decode_bytes(bytes, &mut pos)?)
+                        .into_owned();
+                client_configs.insert(
+                    ssid.clone(),
+                    ClientConfiguration {
+                        ssid: ssid.clone(),
+                        password,
+                        channel: None,
+                        ..Default::default()
+                    },
+                );
+            } else {
+                let identity =
+                    String::from_utf8_lossy(WifiManager::decode_bytes(bytes, &mut pos)?)
+                        .into_owned();
+                let username =
+                    String::from_utf8_lossy(WifiManager::decode_bytes(bytes, &mut pos)?)
+                        .into_owned();
+                let password =
+                    String::from_utf8_lossy(WifiManager::decode_bytes(bytes, &mut pos)?)
+                        .into_owned();
+                let has_ca_cert = WifiManager::decode_u8(bytes, &mut pos)?;
+                let ca_cert = if has_ca_cert == 0 {
+                    None
+                } else {
+                    Some(WifiManager::decode_bytes(bytes, &mut pos)?.to_vec())
+                };
+                client_configs.insert(
+                    ssid.clone(),
+                    ClientConfiguration {
+                        ssid: ssid.clone(),
+                        auth_method: AuthMethod::WPA2Enterprise,
+                        channel: None,
+                        ..Default::default()
+                    },
+                );
+                enterprise_configs.insert(
+                    ssid.clone(),
+                    EnterpriseCredentials {
+                        identity,
+                        username,
+                        password,
+                        ca_cert,
+                    },
+                );
+            }
+
+            let has_static_ip = WifiManager::decode_u8(bytes, &mut pos)?;
+            if has_static_ip != 0 {
+                let address = WifiManager::decode_ipv4(bytes, &mut pos)?;
+                let netmask = WifiManager::decode_ipv4(bytes, &mut pos)?;
+                let gateway = WifiManager::decode_ipv4(bytes, &mut pos)?;
+                let dns = WifiManager::decode_ipv4(bytes, &mut pos)?;
+                static_ip_configs.insert(
+                    ssid,
+                    Ipv4Settings {
+                        address,
+                        netmask,
+                        gateway,
+                        dns,
+                    },
+                );
+            }
+        }
+        Some((client_configs, enterprise_configs, static_ip_configs))
+    }
+
+    fn save_configs_to_nvs(nvs: &EspDefaultNvs, configs: &WifiManagerConfig) {
+        let bytes = WifiManager::serialize_configs(configs);
+        if bytes.len() > NVS_NETWORKS_MAX_LEN {
+            println!(
+                "Wifi networks blob is {} bytes, over the {} byte budget; not persisting to NVS",
+                bytes.len(),
+                NVS_NETWORKS_MAX_LEN
+            );
+            return;
+        }
+        if nvs.set_raw(NVS_NETWORKS_KEY, &bytes).is_err() {
+            println!("Failed to persist wifi networks to NVS");
+        }
+    }
+
+    fn load_configs_from_nvs(
+        nvs: &EspDefaultNvs,
+    ) -> (
+        HashMap<SSID, ClientConfiguration>,
+        HashMap<SSID, EnterpriseCredentials>,
+        HashMap<SSID, Ipv4Settings>,
+    ) {
+        let len = match nvs.len(NVS_NETWORKS_KEY) {
+            Ok(Some(len)) => len,
+            Ok(None) => return (HashMap::new(), HashMap::new(), HashMap::new()),
+            Err(_) => {
+                println!("Failed to query size of stored wifi networks in NVS");
+                return (HashMap::new(), HashMap::new(), HashMap::new());
+            }
+        };
+        let mut buf = vec![0u8; len];
+        match nvs.get_raw(NVS_NETWORKS_KEY, &mut buf) {
+            Ok(Some(bytes)) => WifiManager::deserialize_configs(bytes).unwrap_or_else(|| {
+                println!("Stored wifi networks were corrupt, starting with none configured");
+                (HashMap::new(), HashMap::new(), HashMap::new())
+            }),
+            Ok(None) => (HashMap::new(), HashMap::new(), HashMap::new()),
+            Err(_) => {
+                println!("Failed to read stored wifi networks from NVS");
+                (HashMap::new(), HashMap::new(), HashMap::new())
+            }
+        }
+    }
+
+    fn configure_eap(ssid: &SSID, creds: &EnterpriseCredentials) -> Result<(), WifiError> {
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_identity(
+                creds.identity.as_ptr(),
+                creds.identity.len() as i32,
+            ))
+            .map_err(|_| WifiError::Fatal(format!("Failed to set EAP identity for {}", ssid)))?;
+            esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_username(
+                creds.username.as_ptr(),
+                creds.username.len() as i32,
+            ))
+            .map_err(|_| WifiError::Fatal(format!("Failed to set EAP username for {}", ssid)))?;
+            esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_password(
+                creds.password.as_ptr(),
+                creds.password.len() as i32,
+            ))
+            .map_err(|_| WifiError::Fatal(format!("Failed to set EAP password for {}", ssid)))?;
+            if let Some(ca_cert) = &creds.ca_cert {
+                esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_ca_cert(
+                    ca_cert.as_ptr(),
+                    ca_cert.len() as i32,
+                ))
+                .map_err(|_| WifiError::Fatal(format!("Failed to set EAP CA cert for {}", ssid)))?;
+            }
+            esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_enable())
+                .map_err(|_| WifiError::Fatal(format!("Failed to enable EAP for {}", ssid)))?;
+        }
+        Ok(())
+    }
+
+    fn read_ip_info(esp_wifi: &EspWifi) -> IpInfo {
+        match esp_wifi.sta_netif().get_ip_info() {
+            Ok(ip_info) => IpInfo {
+                address: ip_info.ip,
+                gateway: ip_info.subnet.gateway,
+                netmask: ip_info.subnet.mask.into(),
+            },
+            Err(_) => IpInfo {
+                address: Ipv4Addr::UNSPECIFIED,
+                gateway: Ipv4Addr::UNSPECIFIED,
+                netmask: Ipv4Addr::UNSPECIFIED,
+            },
+        }
+    }
+
+    fn apply_static_ip(esp_wifi: &EspWifi, static_ip: &Ipv4Settings) -> Result<(), WifiError> {
+        let netif_handle = esp_wifi.sta_netif().handle();
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::esp_netif_dhcpc_stop(netif_handle))
+                .map_err(|_| WifiError::Fatal("Failed to stop DHCP client".into()))?;
+
+            let ip_info = esp_idf_sys::esp_netif_ip_info_t {
+                ip: WifiManager::ipv4_to_esp_ip4(static_ip.address),
+                netmask: WifiManager::ipv4_to_esp_ip4(static_ip.netmask),
+                gw: WifiManager::ipv4_to_esp_ip4(static_ip.gateway),
+            };
+            esp_idf_sys::esp!(esp_idf_sys::esp_netif_set_ip_info(netif_handle, &ip_info))
+                .map_err(|_| WifiError::Fatal("Failed to set static IP".into()))?;
+
+            let mut dns_info = esp_idf_sys::esp_netif_dns_info_t {
+                ip: esp_idf_sys::esp_ip_addr_t {
+                    u_addr: esp_idf_sys::esp_ip_addr_t__bindgen_ty_1 {
+                        ip4: WifiManager::ipv4_to_esp_ip4(static_ip.dns),
+                    },
+                    type_: esp_idf_sys::esp_ip_addr_type_t_IPADDR_TYPE_V4,
+                },
+            };
+            esp_idf_sys::esp!(esp_idf_sys::esp_netif_set_dns_info(
+                netif_handle,
+                esp_idf_sys::esp_netif_dns_type_t_ESP_NETIF_DNS_MAIN,
+                &mut dns_info,
+            ))
+            .map_err(|_| WifiError::Fatal("Failed to set static DNS server".into()))?;
+        }
+        Ok(())
+    }
+
+    fn clear_static_ip(esp_wifi: &EspWifi) {
+        let netif_handle = esp_wifi.sta_netif().handle();
+        unsafe {
+            if esp_idf_sys::esp!(esp_idf_sys::esp_netif_dhcpc_start(netif_handle)).is_err() {
+                println!("Failed to restart DHCP client");
+            }
+
+            let mut dns_info = esp_idf_sys::esp_netif_dns_info_t {
+                ip: esp_idf_sys::esp_ip_addr_t {
+                    u_addr: esp_idf_sys::esp_ip_addr_t__bindgen_ty_1 {
+                        ip4: WifiManager::ipv4_to_esp_ip4(Ipv4Addr::UNSPECIFIED),
+                    },
+                    type_: esp_idf_sys::esp_ip_addr_type_t_IPADDR_TYPE_V4,
+                },
+            };
+            if esp_idf_sys::esp!(esp_idf_sys::esp_netif_set_dns_info(
+                netif_handle,
+                esp_idf_sys::esp_netif_dns_type_t_ESP_NETIF_DNS_MAIN,
+                &mut dns_info,
+            ))
+            .is_err()
+            {
+                println!("Failed to reset DNS server to automatic");
+            }
+        }
+    }
+
+    fn ipv4_to_esp_ip4(addr: Ipv4Addr) -> esp_idf_sys::esp_ip4_addr_t {
+        esp_idf_sys::esp_ip4_addr_t {
+            addr: u32::from_be_bytes(addr.octets()),
+        }
+    }
+
     fn reconfigure_wifi(
         config: &WifiManagerConfig,
         mut esp_wifi: EspWifi,
@@ -115,7 +644,8 @@ impl WifiManager {
                 return (esp_wifi, Err(scan_result.unwrap_err().into()));
             }
 
-            let aps = scan_result.unwrap().into_iter();
+            let mut aps: Vec<_> = scan_result.unwrap().into_iter().collect();
+            aps.sort_by(|a, b| b.signal_strength.cmp(&a.signal_strength));
 
             for ap in aps {
                 let client_config = config.client_configs.get(&ap.ssid);
@@ -131,18 +661,53 @@ impl WifiManager {
                         return (esp_wifi, Ok(WifiStatus::Disconnected));
                     };
 
+                    match config.enterprise_configs.get(&ap.ssid) {
+                        Some(creds) => {
+                            if let Err(err) = WifiManager::configure_eap(&ap.ssid, creds) {
+                                return (esp_wifi, Err(err));
+                            }
+                        }
+                        None => unsafe {
+                            esp_idf_sys::esp_wifi_sta_wpa2_ent_disable();
+                        },
+                    }
+
                     let is_error = esp_wifi.set_configuration(&overall_config).is_err();
                     if !is_error {
+                        match config.static_ip_configs.get(&ap.ssid) {
+                            Some(static_ip) => {
+                                if let Err(err) = WifiManager::apply_static_ip(&esp_wifi, static_ip)
+                                {
+                                    return (esp_wifi, Err(err));
+                                }
+                            }
+                            None => WifiManager::clear_static_ip(&esp_wifi),
+                        }
+                        let ip_info = WifiManager::read_ip_info(&esp_wifi);
                         return (
                             esp_wifi,
                             Ok(WifiStatus::Connected(
                                 client_config.unwrap().ssid.clone(),
                                 ap.signal_strength,
+                                ip_info,
                             )),
                         );
                     }
                 }
             }
+
+            if config.ap_fallback {
+                if let Some(ap_config) = config.ap_config.clone() {
+                    let ssid = ap_config.ssid.clone();
+                    let is_error = esp_wifi
+                        .set_configuration(&Configuration::AccessPoint(ap_config))
+                        .is_err();
+                    if !is_error {
+                        return (esp_wifi, Ok(WifiStatus::ApOnly(ssid)));
+                    }
+                }
+            }
+
             (esp_wifi, Ok(WifiStatus::Disconnected))
         }
     }
@@ -155,6 +720,7 @@ impl WifiManager {
         WifiManager {
             command_sender,
             status_receiver,
+            last_status: WifiStatus::Disconnected,
             connection_thread: thread_builder
                 .spawn(move || {
                     let netif_stack = Arc::new(match EspNetifStack::new() {
@@ -169,17 +735,32 @@ impl WifiManager {
                         Ok(nvs) => nvs,
                         Err(_) => panic!("Couldn't create EspDefaultNvs"),
                     });
+                    let storage_nvs = Arc::clone(&default_nvs);
 
                     let mut esp_wifi =
                         EspWifi::new(netif_stack, sys_loop_stack, default_nvs).unwrap();
 
+                    let (client_configs, enterprise_configs, static_ip_configs) =
+                        WifiManager::load_configs_from_nvs(&storage_nvs);
                     let mut configs: WifiManagerConfig = WifiManagerConfig {
-                        client_configs: HashMap::new(),
+                        client_configs,
+                        enterprise_configs,
+                        static_ip_configs,
                         ap_config: None,
+                        ap_fallback: false,
+                        fixed_channel: None,
                     };
+                    let mut captive_portal: Option<CaptivePortalDns> = None;
+                    let mut connection_state = ConnectionState::Connecting;
+                    let mut backoff = WIFI_BACKOFF_MIN;
+                    let mut connected_info: Option<(SSID, WifiSignalStrength, IpInfo)> = None;
 
                     loop {
-                        let status = match command_receiver.recv_timeout(WIFI_SCAN_PERIOD) {
+                        let wait = match connection_state {
+                            ConnectionState::Connected => WIFI_LIVENESS_CHECK_PERIOD,
+                            ConnectionState::Connecting | ConnectionState::Backoff => backoff,
+                        };
+                        let status = match command_receiver.recv_timeout(wait) {
                             Ok(c) => match c {
                                 WifiCommand::ConnectWPA2PSK(ssid, key) => {
                                     configs = WifiManager::connect_wpa2_psk(configs, ssid, key);
@@ -188,7 +769,48 @@ impl WifiManager {
                                     esp_wifi = result.0;
 
                                     match result.1 {
-                                        Ok(status) => status,
+                                        Ok(status) => {
+                                            WifiManager::save_configs_to_nvs(&storage_nvs, &configs);
+                                            status
+                                        }
+                                        Err(err) => WifiStatus::Error(err),
+                                    }
+                                }
+                                WifiCommand::ConnectWPA2PSKStatic(ssid, key, static_ip) => {
+                                    configs = WifiManager::connect_wpa2_psk_static(
+                                        configs, ssid, key, static_ip,
+                                    );
+                                    let result = WifiManager::reconfigure_wifi(&configs, esp_wifi);
+
+                                    esp_wifi = result.0;
+
+                                    match result.1 {
+                                        Ok(status) => {
+                                            WifiManager::save_configs_to_nvs(&storage_nvs, &configs);
+                                            status
+                                        }
+                                        Err(err) => WifiStatus::Error(err),
+                                    }
+                                }
+                                WifiCommand::ConnectWPA2Enterprise {
+                                    ssid,
+                                    identity,
+                                    username,
+                                    password,
+                                    ca_cert,
+                                } => {
+                                    configs = WifiManager::connect_wpa2_enterprise(
+                                        configs, ssid, identity, username, password, ca_cert,
+                                    );
+                                    let result = WifiManager::reconfigure_wifi(&configs, esp_wifi);
+
+                                    esp_wifi = result.0;
+
+                                    match result.1 {
+                                        Ok(status) => {
+                                            WifiManager::save_configs_to_nvs(&storage_nvs, &configs);
+                                            status
+                                        }
                                         Err(err) => WifiStatus::Error(err),
                                     }
                                 }
@@ -198,14 +820,92 @@ impl WifiManager {
 
                                     esp_wifi = result.0;
 
+                                    match result.1 {
+                                        Ok(status) => status,
+                                        Err(err) => WifiStatus::Error(err),
+                                    }
+                                }
+                                WifiCommand::ForgetNetwork(ssid) => {
+                                    configs = WifiManager::forget_network(configs, &ssid);
+                                    WifiManager::save_configs_to_nvs(&storage_nvs, &configs);
+                                    let result = WifiManager::reconfigure_wifi(&configs, esp_wifi);
+
+                                    esp_wifi = result.0;
+
+                                    match result.1 {
+                                        Ok(status) => status,
+                                        Err(err) => WifiStatus::Error(err),
+                                    }
+                                }
+                                WifiCommand::SetApFallback(ap_fallback) => {
+                                    configs = WifiManager::set_ap_fallback(configs, ap_fallback);
+                                    let result = WifiManager::reconfigure_wifi(&configs, esp_wifi);
+
+                                    esp_wifi = result.0;
+
+                                    match result.1 {
+                                        Ok(status) => status,
+                                        Err(err) => WifiStatus::Error(err),
+                                    }
+                                }
+                                WifiCommand::SetFixedChannel(fixed_channel) => {
+                                    configs = WifiManager::set_fixed_channel(configs, fixed_channel);
+                                    let result = WifiManager::reconfigure_wifi(&configs, esp_wifi);
+
+                                    esp_wifi = result.0;
+
                                     match result.1 {
                                         Ok(status) => status,
                                         Err(err) => WifiStatus::Error(err),
                                     }
                                 }
                             },
-                            Err(_) => WifiStatus::Disconnected,
+                            Err(_) => {
+                                let still_connected = connection_state == ConnectionState::Connected
+                                    && esp_wifi.is_connected().unwrap_or(false);
+                                if still_connected {
+                                    let (ssid, signal_strength, ip_info) =
+                                        connected_info.clone().unwrap();
+                                    WifiStatus::Connected(ssid, signal_strength, ip_info)
+                                } else {
+                                    let result = WifiManager::reconfigure_wifi(&configs, esp_wifi);
+
+                                    esp_wifi = result.0;
+
+                                    match result.1 {
+                                        Ok(status) => status,
+                                        Err(err) => WifiStatus::Error(err),
+                                    }
+                                }
+                            }
                         };
+
+                        match &status {
+                            WifiStatus::Connected(ssid, signal_strength, ip_info) => {
+                                connection_state = ConnectionState::Connected;
+                                connected_info = Some((ssid.clone(), *signal_strength, *ip_info));
+                                backoff = WIFI_BACKOFF_MIN;
+                            }
+                            _ => {
+                                connection_state = ConnectionState::Backoff;
+                                connected_info = None;
+                                backoff = (backoff * 2).min(WIFI_SCAN_PERIOD);
+                            }
+                        }
+
+                        match &status {
+                            WifiStatus::ApOnly(_) => {
+                                if captive_portal.is_none() {
+                                    captive_portal = Some(CaptivePortalDns::spawn());
+                                }
+                            }
+                            _ => {
+                                if let Some(portal) = captive_portal.take() {
+                                    portal.stop();
+                                }
+                            }
+                        }
+
                         status_sender.send(status).unwrap();
                     }
                 })
@@ -222,6 +922,67 @@ impl WifiManager {
             .send(WifiCommand::ConnectWPA2PSK(ssid, key))
     }
 
+    pub fn add_network_wpa2_psk_static(
+        &mut self,
+        ssid: SSID,
+        key: PSKKey,
+        static_ip: Ipv4Settings,
+    ) -> Result<(), SendError<WifiCommand>> {
+        self.command_sender
+            .send(WifiCommand::ConnectWPA2PSKStatic(ssid, key, static_ip))
+    }
+
+    pub fn add_network_wpa2_enterprise(
+        &mut self,
+        ssid: SSID,
+        identity: String,
+        username: String,
+        password: String,
+        ca_cert: Option<Vec<u8>>,
+    ) -> Result<(), SendError<WifiCommand>> {
+        self.command_sender
+            .send(WifiCommand::ConnectWPA2Enterprise {
+                ssid,
+                identity,
+                username,
+                password,
+                ca_cert,
+            })
+    }
+
+    pub fn forget_network(&mut self, ssid: SSID) -> Result<(), SendError<WifiCommand>> {
+        self.command_sender.send(WifiCommand::ForgetNetwork(ssid))
+    }
+
+    pub fn poll_status(&mut self) -> Option<WifiStatus> {
+        let mut latest = None;
+        while let Ok(status) = self.status_receiver.try_recv() {
+            latest = Some(status);
+        }
+        if let Some(status) = &latest {
+            self.last_status = status.clone();
+        }
+        latest
+    }
+
+    pub fn current_status(&mut self) -> WifiStatus {
+        self.poll_status();
+        self.last_status.clone()
+    }
+
+    pub fn set_ap_fallback(&mut self, ap_fallback: bool) -> Result<(), SendError<WifiCommand>> {
+        self.command_sender
+            .send(WifiCommand::SetApFallback(ap_fallback))
+    }
+
+    pub fn set_fixed_channel(
+        &mut self,
+        fixed_channel: Option<u8>,
+    ) -> Result<(), SendError<WifiCommand>> {
+        self.command_sender
+            .send(WifiCommand::SetFixedChannel(fixed_channel))
+    }
+
     pub fn set_ap_wpa2_psk(
         &mut self,
         ssid: SSID,
@@ -231,3 +992,148 @@ impl WifiManager {
             .send(WifiCommand::CreateApWPA2PSK(ssid, key))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_networks() -> WifiManagerConfig {
+        let mut configs = WifiManagerConfig {
+            client_configs: HashMap::new(),
+            enterprise_configs: HashMap::new(),
+            static_ip_configs: HashMap::new(),
+            ap_config: None,
+            ap_fallback: false,
+            fixed_channel: None,
+        };
+        configs.client_configs.insert(
+            "home".to_string(),
+            ClientConfiguration {
+                ssid: "home".to_string(),
+                password: "hunter2".to_string(),
+                channel: None,
+                ..Default::default()
+            },
+        );
+        configs.client_configs.insert(
+            "office".to_string(),
+            ClientConfiguration {
+                ssid: "office".to_string(),
+                auth_method: AuthMethod::WPA2Enterprise,
+                channel: None,
+                ..Default::default()
+            },
+        );
+        configs.enterprise_configs.insert(
+            "office".to_string(),
+            EnterpriseCredentials {
+                identity: "employee".to_string(),
+                username: "employee".to_string(),
+                password: "swordfish".to_string(),
+                ca_cert: Some(vec![1, 2, 3, 4]),
+            },
+        );
+        configs
+    }
+
+    #[test]
+    fn round_trips_plain_and_enterprise_configs() {
+        let configs = config_with_networks();
+        let bytes = WifiManager::serialize_configs(&configs);
+        let (client_configs, enterprise_configs, static_ip_configs) =
+            WifiManager::deserialize_configs(&bytes).unwrap();
+
+        assert_eq!(client_configs.len(), 2);
+        assert_eq!(client_configs["home"].password, "hunter2");
+        assert_eq!(
+            client_configs["office"].auth_method,
+            AuthMethod::WPA2Enterprise
+        );
+
+        let office_creds = &enterprise_configs["office"];
+        assert_eq!(office_creds.identity, "employee");
+        assert_eq!(office_creds.ca_cert, Some(vec![1, 2, 3, 4]));
+
+        assert!(static_ip_configs.is_empty());
+    }
+
+    #[test]
+    fn round_trips_static_ip_settings() {
+        let mut configs = config_with_networks();
+        configs.static_ip_configs.insert(
+            "home".to_string(),
+            Ipv4Settings {
+                address: Ipv4Addr::new(192, 168, 1, 50),
+                netmask: Ipv4Addr::new(255, 255, 255, 0),
+                gateway: Ipv4Addr::new(192, 168, 1, 1),
+                dns: Ipv4Addr::new(1, 1, 1, 1),
+            },
+        );
+
+        let bytes = WifiManager::serialize_configs(&configs);
+        let (_, _, static_ip_configs) = WifiManager::deserialize_configs(&bytes).unwrap();
+
+        let home_static_ip = &static_ip_configs["home"];
+        assert_eq!(home_static_ip.address, Ipv4Addr::new(192, 168, 1, 50));
+        assert_eq!(home_static_ip.dns, Ipv4Addr::new(1, 1, 1, 1));
+        assert!(!static_ip_configs.contains_key("office"));
+    }
+
+    #[test]
+    fn rejects_truncated_blob_instead_of_panicking() {
+        let configs = config_with_networks();
+        let bytes = WifiManager::serialize_configs(&configs);
+
+        for len in 0..bytes.len() {
+            assert!(WifiManager::deserialize_configs(&bytes[..len]).is_none());
+        }
+    }
+
+    fn build_dns_query(domain: &str) -> Vec<u8> {
+        let mut query = Vec::new();
+        query.extend_from_slice(&[0xab, 0xcd]); // transaction id
+        query.extend_from_slice(&[0x01, 0x00]); // standard query, recursion desired
+        query.extend_from_slice(&[0x00, 0x01]); // QDCOUNT
+        query.extend_from_slice(&[0x00, 0x00]); // ANCOUNT
+        query.extend_from_slice(&[0x00, 0x00]); // NSCOUNT
+        query.extend_from_slice(&[0x00, 0x00]); // ARCOUNT
+        for label in domain.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0x00); // root label
+        query.extend_from_slice(&[0x00, 0x01]); // QTYPE A
+        query.extend_from_slice(&[0x00, 0x01]); // QCLASS IN
+        query
+    }
+
+    #[test]
+    fn redirects_a_record_query_to_captive_portal_gateway() {
+        let query = build_dns_query("connectivitycheck.gstatic.com");
+        let response = WifiManager::build_dns_redirect(&query).unwrap();
+
+        assert_eq!(&response[0..2], &query[0..2]); // transaction id preserved
+        assert_eq!(&response[2..4], &[0x81, 0x80]); // standard response, no error
+        let rdata_start = response.len() - 4;
+        assert_eq!(
+            &response[rdata_start..],
+            &CAPTIVE_PORTAL_GATEWAY.octets()
+        );
+    }
+
+    #[test]
+    fn rejects_query_with_no_questions() {
+        let mut query = build_dns_query("example.com");
+        query[4] = 0x00;
+        query[5] = 0x00; // QDCOUNT = 0
+        assert!(WifiManager::build_dns_redirect(&query).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_query() {
+        let query = build_dns_query("example.com");
+        for len in 0..query.len() {
+            assert!(WifiManager::build_dns_redirect(&query[..len]).is_none());
+        }
+    }
+}